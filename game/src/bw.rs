@@ -10,6 +10,7 @@ use quick_error::quick_error;
 
 use crate::app_messages::{MapInfo, Settings};
 
+pub mod apm;
 pub mod commands;
 pub mod list;
 pub mod unit;
@@ -0,0 +1,268 @@
+//! Rolling APM / EAPM tracking for each player.
+//!
+//! Fed with every command that BW processes (Both live and replay commands, so it
+//! works for players, observers and replays alike), and counts actions over the last
+//! `WINDOW_FRAMES` game frames.
+//!
+//! Players are indexed by their unique player id, not the game player id which
+//! is shared between teammates in team games.
+//!
+//! EAPM ("effective" APM) excludes actions that are very likely spam:
+//! - Exact repeats of the previous command within `EAPM_SPAM_FRAMES`.
+//! - A selection that is replaced by another selection within `EAPM_SPAM_FRAMES`,
+//!   e.g. cycling through control groups without giving orders. The later selection
+//!   still counts.
+
+use std::collections::VecDeque;
+
+use super::commands::id;
+
+/// Game frame length on Fastest, which is what APM is conventionally measured with.
+const MS_PER_FRAME: u32 = 42;
+/// Actions that are older than this are no longer counted.
+/// ~1 minute, long enough for the value to not jump around after every action.
+const WINDOW_FRAMES: u32 = 60_000 / MS_PER_FRAME;
+/// Early in the game, APM is calculated over the elapsed time instead of the entire
+/// window, but at least over this many frames (~5 seconds), as otherwise the first
+/// few actions would show absurdly high values.
+const MIN_ELAPSED_FRAMES: u32 = 5_000 / MS_PER_FRAME;
+/// See module comment. ~1/3 seconds.
+const EAPM_SPAM_FRAMES: u32 = 8;
+
+#[derive(Default)]
+pub struct ApmTracker {
+    players: [PlayerActions; 8],
+}
+
+#[derive(Default)]
+struct PlayerActions {
+    /// Frames of all actions in the window, oldest first.
+    actions: VecDeque<u32>,
+    /// Frames of effective actions in the window, oldest first.
+    effective_actions: VecDeque<u32>,
+    last_frame: u32,
+    /// Bytes of the previous action, empty if there hasn't been any.
+    last_command: Vec<u8>,
+    last_effective: bool,
+}
+
+impl ApmTracker {
+    /// Clears all actions, should be called when a game starts.
+    pub fn reset(&mut self) {
+        *self = ApmTracker::default();
+    }
+
+    /// Records a single command (As split by `commands::iter_commands`) that `player`
+    /// sent, which is being processed on game frame `frame`.
+    pub fn record_command(&mut self, player: u8, frame: u32, command: &[u8]) {
+        let actions = match self.players.get_mut(player as usize) {
+            Some(s) => s,
+            None => return,
+        };
+        let command_id = match command.first() {
+            Some(&s) => s,
+            None => return,
+        };
+        if !is_action(command_id) {
+            return;
+        }
+        let oldest_counted = frame.saturating_sub(WINDOW_FRAMES - 1);
+        remove_older_than(&mut actions.actions, oldest_counted);
+        remove_older_than(&mut actions.effective_actions, oldest_counted);
+
+        let is_spam_timing = !actions.last_command.is_empty()
+            && frame.saturating_sub(actions.last_frame) < EAPM_SPAM_FRAMES;
+        let effective = if is_spam_timing {
+            if command == &actions.last_command[..] {
+                false
+            } else {
+                if is_selection(command_id)
+                    && is_selection(actions.last_command[0])
+                    && actions.last_effective
+                {
+                    // Previous selection got replaced without doing anything with it.
+                    actions.effective_actions.pop_back();
+                }
+                true
+            }
+        } else {
+            true
+        };
+
+        actions.actions.push_back(frame);
+        if effective {
+            actions.effective_actions.push_back(frame);
+        }
+        actions.last_frame = frame;
+        actions.last_command.clear();
+        actions.last_command.extend_from_slice(command);
+        actions.last_effective = effective;
+    }
+
+    /// Returns APM of `player` at game frame `frame`.
+    #[allow(dead_code)] // To be shown in the replay/observer UI
+    pub fn apm(&self, player: u8, frame: u32) -> u32 {
+        match self.players.get(player as usize) {
+            Some(actions) => actions_per_minute(&actions.actions, frame),
+            None => 0,
+        }
+    }
+
+    /// Returns EAPM of `player` at game frame `frame`.
+    #[allow(dead_code)] // To be shown in the replay/observer UI
+    pub fn eapm(&self, player: u8, frame: u32) -> u32 {
+        match self.players.get(player as usize) {
+            Some(actions) => actions_per_minute(&actions.effective_actions, frame),
+            None => 0,
+        }
+    }
+}
+
+fn remove_older_than(actions: &mut VecDeque<u32>, frame: u32) {
+    while actions.front().filter(|&&x| x < frame).is_some() {
+        actions.pop_front();
+    }
+}
+
+fn actions_per_minute(actions: &VecDeque<u32>, frame: u32) -> u32 {
+    let oldest_counted = frame.saturating_sub(WINDOW_FRAMES - 1);
+    let count = actions
+        .iter()
+        .rev()
+        .take_while(|&&x| x >= oldest_counted)
+        .count() as u64;
+    let frames = frame.clamp(MIN_ELAPSED_FRAMES, WINDOW_FRAMES) as u64;
+    (count * 60_000 / (frames * MS_PER_FRAME as u64)) as u32
+}
+
+/// Returns false for commands that are sent without player doing anything.
+fn is_action(command_id: u8) -> bool {
+    !matches!(
+        command_id,
+        id::NOP
+            | id::SYNC
+            | id::REPLAY_SPEED
+            | id::REPLAY_SEEK
+            | id::LEAVE_GAME
+            | id::CHAT
+            | id::SET_TURN_RATE
+            | id::SET_NETWORK_SPEED
+    )
+}
+
+fn is_selection(command_id: u8) -> bool {
+    matches!(
+        command_id,
+        id::SELECT
+            | id::SELECT_ADD
+            | id::SELECT_REMOVE
+            | id::SELECT_EXT
+            | id::SELECT_ADD_EXT
+            | id::SELECT_REMOVE_EXT
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RIGHT_CLICK: &[u8] = &[0x14, 0x10, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    const RIGHT_CLICK2: &[u8] = &[0x14, 0x30, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    const SELECT: &[u8] = &[0x09, 0x01, 0x05, 0x00];
+    const SELECT2: &[u8] = &[0x09, 0x01, 0x06, 0x00];
+
+    #[test]
+    fn rolling_window() {
+        let mut apm = ApmTracker::default();
+        // One action every 47 frames (~2 seconds) for the first minute
+        for i in 0..30 {
+            let command = if i & 1 == 0 {
+                RIGHT_CLICK
+            } else {
+                RIGHT_CLICK2
+            };
+            apm.record_command(0, 1 + i * 47, command);
+        }
+        assert_eq!(apm.apm(0, WINDOW_FRAMES), 30);
+        assert_eq!(apm.apm(1, WINDOW_FRAMES), 0);
+        // Nothing for 30 seconds, only the latter half of the actions is in window.
+        let later = WINDOW_FRAMES + WINDOW_FRAMES / 2;
+        assert_eq!(apm.apm(0, later), 14);
+        // Recording a new action drops old actions out of the window.
+        apm.record_command(0, later, RIGHT_CLICK);
+        assert_eq!(apm.players[0].actions.len(), 15);
+        assert_eq!(apm.apm(0, later), 15);
+        assert_eq!(apm.apm(0, later + WINDOW_FRAMES), 0);
+    }
+
+    #[test]
+    fn early_game() {
+        let mut apm = ApmTracker::default();
+        // 16 actions in the first ~30 seconds are calculated as 32 APM, not 16.
+        for i in 0..16 {
+            let command = if i & 1 == 0 {
+                RIGHT_CLICK
+            } else {
+                RIGHT_CLICK2
+            };
+            apm.record_command(0, 1 + i * 47, command);
+        }
+        assert_eq!(apm.apm(0, WINDOW_FRAMES / 2), 32);
+        // Minimum elapsed time keeps the first actions from showing huge values
+        let mut apm = ApmTracker::default();
+        apm.record_command(0, 1, RIGHT_CLICK);
+        assert_eq!(apm.apm(0, 1), 12);
+        assert_eq!(apm.apm(0, 0), 12);
+    }
+
+    #[test]
+    fn non_actions_ignored() {
+        let mut apm = ApmTracker::default();
+        apm.record_command(0, 10, &[id::SYNC, 0, 0, 0, 0, 0, 0]);
+        apm.record_command(0, 20, &[id::NOP]);
+        apm.record_command(0, 30, &[id::SET_TURN_RATE, 0x18]);
+        apm.record_command(9, 30, RIGHT_CLICK);
+        apm.record_command(0, 40, &[]);
+        assert_eq!(apm.apm(0, 100), 0);
+        assert!(apm.players[0].actions.is_empty());
+    }
+
+    #[test]
+    fn eapm_filter() {
+        let mut apm = ApmTracker::default();
+        // Spam clicking same spot, only the first one is effective
+        apm.record_command(0, 10, RIGHT_CLICK);
+        apm.record_command(0, 12, RIGHT_CLICK);
+        apm.record_command(0, 14, RIGHT_CLICK);
+        // Different command is effective even if quick
+        apm.record_command(0, 15, RIGHT_CLICK2);
+        // Same command again after a while is effective
+        apm.record_command(0, 50, RIGHT_CLICK2);
+        assert_eq!(apm.players[0].actions.len(), 5);
+        assert_eq!(apm.players[0].effective_actions.len(), 3);
+
+        // Selection replaced by another selection quickly, only the latter is effective
+        apm.record_command(0, 100, SELECT);
+        apm.record_command(0, 104, SELECT2);
+        assert_eq!(apm.players[0].effective_actions.len(), 4);
+        // Selection that is followed by an order stays effective
+        apm.record_command(0, 106, RIGHT_CLICK);
+        apm.record_command(0, 200, SELECT);
+        apm.record_command(0, 300, SELECT2);
+        assert_eq!(apm.players[0].actions.len(), 10);
+        assert_eq!(apm.players[0].effective_actions.len(), 7);
+        // 300 frames = 12.6 seconds
+        assert_eq!(apm.apm(0, 300), 47);
+        assert_eq!(apm.eapm(0, 300), 33);
+    }
+
+    #[test]
+    fn reset() {
+        let mut apm = ApmTracker::default();
+        apm.record_command(3, 10, RIGHT_CLICK);
+        assert_ne!(apm.apm(3, 10), 0);
+        apm.reset();
+        assert_eq!(apm.apm(3, 10), 0);
+        assert_eq!(apm.eapm(3, 10), 0);
+    }
+}
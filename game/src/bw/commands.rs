@@ -6,10 +6,18 @@ use byteorder::{ByteOrder, LittleEndian};
 
 pub mod id {
     pub const NOP: u8 = 0x5;
+    pub const SELECT: u8 = 0x9;
+    pub const SELECT_ADD: u8 = 0xa;
+    pub const SELECT_REMOVE: u8 = 0xb;
     pub const SYNC: u8 = 0x37;
     pub const REPLAY_SPEED: u8 = 0x56;
+    pub const LEAVE_GAME: u8 = 0x57;
+    pub const CHAT: u8 = 0x5c;
     pub const REPLAY_SEEK: u8 = 0x5d;
     pub const SET_TURN_RATE: u8 = 0x5f;
+    pub const SELECT_EXT: u8 = 0x63;
+    pub const SELECT_ADD_EXT: u8 = 0x64;
+    pub const SELECT_REMOVE_EXT: u8 = 0x65;
     pub const SET_NETWORK_SPEED: u8 = 0x66;
 }
 
@@ -14,12 +14,14 @@ use smallvec::SmallVec;
 use winapi::um::errhandlingapi::SetLastError;
 use winapi::um::libloaderapi::GetModuleHandleW;
 
+use bw_vector::{bw_vector_get, bw_vector_get_mut};
 use scr_analysis::{scarf, DatType};
 use sdf_cache::{InitSdfCache, SdfCache};
 use shader_replaces::ShaderReplaces;
 pub use thiscall::Thiscall;
 
 use crate::app_messages::{MapInfo, Settings};
+use crate::bw::apm::ApmTracker;
 use crate::bw::unit::{Unit, UnitIterator};
 use crate::bw::{self, Bw, FowSpriteIterator, SnpFunctions, StormPlayerId};
 use crate::bw::{commands, UserLatency};
@@ -29,6 +31,7 @@ use crate::windows;
 use crate::{game_thread, GameThreadMessage};
 
 mod bw_hash_table;
+mod bw_vector;
 mod dialog_hook;
 mod file_hook;
 mod game;
@@ -185,6 +188,7 @@ pub struct BwScr {
     // Path that reads/writes of CSettings.json will be redirected to
     settings_file_path: RwLock<String>,
     detection_status_copy: Mutex<Vec<u32>>,
+    apm: Mutex<ApmTracker>,
 }
 
 struct SendPtr<T>(T);
@@ -600,7 +604,28 @@ pub mod scr {
         pub data: [u8; 0x28],
         pub shader_id: u32,
         pub more_data: [u8; 0x24],
-        pub shader_constants: [f32; 0x14],
+        pub shader_constants: ShaderConstants,
+    }
+
+    /// Pixel shader constant buffer of a draw command.
+    ///
+    /// The meaning of each slot depends on the shader that the command uses, so
+    /// the array is private and writes go through the named setters.
+    #[repr(transparent)]
+    pub struct ShaderConstants([f32; 0x14]);
+
+    impl ShaderConstants {
+        /// Mask shader (`mask.hlsl`) slot 0: `useNewMask`.
+        /// 0.0 keeps BW's behaviour of unexplored areas being black.
+        pub fn set_mask_use_new_mask(&mut self, value: f32) {
+            self.0[0] = value;
+        }
+
+        /// Mask shader (`mask.hlsl`) slot 1: `showNetworkStalled`.
+        /// 1.0 shades the game to show that the network is stalled.
+        pub fn set_mask_show_network_stalled(&mut self, value: f32) {
+            self.0[1] = value;
+        }
     }
 
     #[repr(C)]
@@ -686,6 +711,7 @@ pub mod scr {
         assert_eq!(size_of::<PrismShaderSet>(), 0x8);
         assert_eq!(size_of::<PrismShader>(), 0x10);
         assert_eq!(size_of::<DrawCommand>(), 0xa0);
+        assert_eq!(size_of::<ShaderConstants>(), 0x50);
         assert_eq!(size_of::<Shader>(), 0x78);
         assert_eq!(size_of::<Sprite>(), 0x28);
     }
@@ -1316,6 +1342,7 @@ impl BwScr {
             dropped_players: AtomicU32::new(0),
             settings_file_path: RwLock::new(String::new()),
             detection_status_copy: Mutex::new(Vec::new()),
+            apm: Mutex::new(ApmTracker::default()),
         })
     }
 
@@ -1356,23 +1383,32 @@ impl BwScr {
                     &self.game_command_lengths,
                 );
                 let mut sync_seen = false;
-                if are_recorded_replay_commands == 0 {
-                    for command in commands::iter_commands(&slice, &self.game_command_lengths) {
-                        match command {
-                            [commands::id::REPLAY_SEEK, rest @ ..] if rest.len() == 4 => {
-                                let frame = LittleEndian::read_u32(rest);
-                                let game = self.game();
-                                if (*game).frame_count > frame {
-                                    self.is_replay_seeking.store(true, Ordering::Relaxed);
-                                }
-                            }
-                            [commands::id::SYNC, ..] | [commands::id::NOP, ..] => {
-                                sync_seen = true;
+                let current_frame = (*self.game()).frame_count;
+                // command_user is the team's main player in team games,
+                // unique_command_user is the player who actually sent the commands.
+                let unique_command_user = self.unique_command_user.resolve();
+                let mut apm = self.apm.lock();
+                for command in commands::iter_commands(&slice, &self.game_command_lengths) {
+                    if unique_command_user < 8 {
+                        apm.record_command(unique_command_user as u8, current_frame, command);
+                    }
+                    if are_recorded_replay_commands != 0 {
+                        continue;
+                    }
+                    match command {
+                        [commands::id::REPLAY_SEEK, rest @ ..] if rest.len() == 4 => {
+                            let frame = LittleEndian::read_u32(rest);
+                            if current_frame > frame {
+                                self.is_replay_seeking.store(true, Ordering::Relaxed);
                             }
-                            _ => (),
                         }
+                        [commands::id::SYNC, ..] | [commands::id::NOP, ..] => {
+                            sync_seen = true;
+                        }
+                        _ => (),
                     }
                 }
+                drop(apm);
 
                 let is_replay = game_thread::is_replay();
                 if !is_replay {
@@ -1846,8 +1882,9 @@ impl BwScr {
                 };
                 for cmd in &mut (*commands).commands {
                     if cmd.shader_id == SHADER_ID_MASK {
-                        cmd.shader_constants[0] = use_new_mask;
-                        cmd.shader_constants[1] = show_network_stalled;
+                        cmd.shader_constants.set_mask_use_new_mask(use_new_mask);
+                        cmd.shader_constants
+                            .set_mask_show_network_stalled(show_network_stalled);
                     }
                 }
                 orig(renderer, commands, width, height)
@@ -2199,6 +2236,7 @@ impl BwScr {
     /// we don't need to and shouldn't reset any network state.
     fn reset_state_for_game_init(&self) {
         self.detection_status_copy.lock().clear();
+        self.apm.lock().reset();
     }
 }
 
@@ -3225,19 +3263,34 @@ unsafe fn step_game_logic_hook(
         //
         // FWIW, it is be fine to rely on (*units).length and (*units).data being
         // constant for the all step_game_logic calls across single game.
-        let detection_status = bw.detection_status_copy.lock();
-        let unit_ptr = (*units).data as *mut bw::Unit;
+        let mut detection_status = bw.detection_status_copy.lock();
+        let unit_count = (*units).length;
+        if detection_status.len() > unit_count {
+            // Shouldn't happen, but if it does, the copy is stale and would restore
+            // detection status to wrong units. The copy gets replaced after orig
+            // below, so this won't keep getting logged.
+            warn!(
+                "Unit array length changed from {} to {}, not restoring detection status \
+                for the extra units",
+                detection_status.len(),
+                unit_count,
+            );
+            detection_status.truncate(unit_count);
+        }
         for (i, value) in detection_status.iter().copied().enumerate() {
-            (*unit_ptr.add(i)).detection_status = value;
+            let unit = bw_vector_get_mut::<bw::Unit>(units, i).unwrap();
+            unit.detection_status = value;
         }
     }
     let ret = orig(param);
     {
         let mut detection_status = bw.detection_status_copy.lock();
         let unit_count = (*units).length;
-        let unit_ptr = (*units).data as *mut bw::Unit;
         detection_status.clear();
-        detection_status.extend((0..unit_count).map(|i| (*unit_ptr.add(i)).detection_status));
+        detection_status.extend((0..unit_count).map(|i| {
+            let unit = bw_vector_get::<bw::Unit>(units, i).unwrap();
+            unit.detection_status
+        }));
     }
     ret
 }
@@ -0,0 +1,298 @@
+//! Helpers for accessing BW's `std::vector`-like `scr::BwVector`.
+//!
+//! `BwVector` is untyped, so the caller has to know the element type. The element
+//! type must match what BW stores in the vector, and since BW may also access the
+//! vector, `T` is expected to not have any copy/move operators / destructor
+//! (Which is true for plain BW structs).
+//!
+//! Functions that grow the vector allocate with BW's allocator, so they can be used
+//! on vectors that BW owns.
+
+use std::mem;
+use std::ptr;
+
+use super::{bw_free, bw_malloc, scr};
+
+/// Capacity that an empty vector grows to at minimum, so that growing
+/// a vector with zero capacity doesn't end up allocating nothing.
+const MIN_GROW_CAPACITY: usize = 4;
+
+type AllocFn = unsafe extern "C" fn(usize) -> *mut u8;
+type FreeFn = unsafe extern "C" fn(*mut u8);
+
+/// Returns reference to element at `index`, or `None` if `index` is out of bounds.
+///
+/// Unsafe as the lifetime of the returned reference is unbounded, and the
+/// caller has to guarantee that `vec` is valid and contains values of `T`.
+pub unsafe fn bw_vector_get<'a, T>(vec: *const scr::BwVector, index: usize) -> Option<&'a T> {
+    if index >= (*vec).length {
+        return None;
+    }
+    Some(&*((*vec).data as *const T).add(index))
+}
+
+/// Mutable variant of `bw_vector_get`.
+pub unsafe fn bw_vector_get_mut<'a, T>(vec: *mut scr::BwVector, index: usize) -> Option<&'a mut T> {
+    if index >= (*vec).length {
+        return None;
+    }
+    Some(&mut *((*vec).data as *mut T).add(index))
+}
+
+/// Removes the last element and returns it, or `None` if the vector is empty.
+#[allow(dead_code)]
+pub unsafe fn bw_vector_pop<T>(vec: *mut scr::BwVector) -> Option<T> {
+    let length = (*vec).length;
+    if length == 0 {
+        return None;
+    }
+    (*vec).length = length - 1;
+    Some(((*vec).data as *mut T).add(length - 1).read())
+}
+
+/// Removes element at `index`, moving all elements after it down by one.
+///
+/// Panics in debug builds if `index` is out of bounds, and in release builds
+/// leaves the vector untouched and returns `None`.
+#[allow(dead_code)]
+pub unsafe fn bw_vector_remove<T>(vec: *mut scr::BwVector, index: usize) -> Option<T> {
+    let length = (*vec).length;
+    debug_assert!(
+        index < length,
+        "Index {index} out of bounds, length {length}"
+    );
+    if index >= length {
+        return None;
+    }
+    let data = (*vec).data as *mut T;
+    let value = data.add(index).read();
+    ptr::copy(data.add(index + 1), data.add(index), length - index - 1);
+    (*vec).length = length - 1;
+    Some(value)
+}
+
+/// Inserts `value` at `index`, moving all elements after it up by one.
+/// Grows the vector if it is full.
+///
+/// Panics if `index` is greater than length.
+#[allow(dead_code)]
+pub unsafe fn bw_vector_insert<T>(vec: *mut scr::BwVector, index: usize, value: T) {
+    bw_vector_insert_with(vec, index, value, bw_malloc, bw_free)
+}
+
+/// `bw_vector_insert` taking the allocation functions as parameters, so that
+/// tests can grow vectors without BW's allocator.
+unsafe fn bw_vector_insert_with<T>(
+    vec: *mut scr::BwVector,
+    index: usize,
+    value: T,
+    alloc: AllocFn,
+    free: FreeFn,
+) {
+    let length = (*vec).length;
+    assert!(
+        index <= length,
+        "Index {index} out of bounds, length {length}"
+    );
+    if length == (*vec).capacity {
+        bw_vector_grow::<T>(vec, length + 1, alloc, free);
+    }
+    let data = (*vec).data as *mut T;
+    ptr::copy(data.add(index), data.add(index + 1), length - index);
+    data.add(index).write(value);
+    (*vec).length = length + 1;
+}
+
+/// Sets length to 0, keeping the allocated capacity for reuse.
+///
+/// Elements are not dropped, which is fine as the elements shouldn't own any resources.
+#[allow(dead_code)]
+pub unsafe fn bw_vector_clear(vec: *mut scr::BwVector) {
+    (*vec).length = 0;
+}
+
+/// Reallocates the vector to have capacity of at least `min_capacity`.
+unsafe fn bw_vector_grow<T>(
+    vec: *mut scr::BwVector,
+    min_capacity: usize,
+    alloc: AllocFn,
+    free: FreeFn,
+) {
+    let old_cap = (*vec).capacity;
+    if old_cap >= min_capacity {
+        return;
+    }
+    let new_cap = old_cap
+        .saturating_mul(2)
+        .max(min_capacity)
+        .max(MIN_GROW_CAPACITY);
+    let new_data = match new_cap.checked_mul(mem::size_of::<T>()) {
+        Some(size) => alloc(size) as *mut T,
+        None => ptr::null_mut(),
+    };
+    assert!(
+        !new_data.is_null(),
+        "Allocation of {new_cap} elements failed"
+    );
+    let old_data = (*vec).data as *mut T;
+    if !old_data.is_null() {
+        ptr::copy_nonoverlapping(old_data, new_data, (*vec).length);
+        free(old_data as *mut u8);
+    }
+    (*vec).data = new_data as *mut libc::c_void;
+    (*vec).capacity = new_cap;
+}
+
+#[cfg(test)]
+unsafe extern "C" fn test_malloc(size: usize) -> *mut u8 {
+    libc::malloc(size) as *mut u8
+}
+
+#[cfg(test)]
+unsafe extern "C" fn test_free(ptr: *mut u8) {
+    libc::free(ptr as *mut libc::c_void)
+}
+
+#[test]
+fn test_bw_vector_get() {
+    let mut values = vec![1u32, 2, 3];
+    let mut vec = scr::BwVector {
+        data: values.as_mut_ptr() as *mut libc::c_void,
+        length: values.len(),
+        capacity: values.capacity(),
+    };
+    unsafe {
+        assert_eq!(bw_vector_get::<u32>(&vec, 0), Some(&1));
+        assert_eq!(bw_vector_get::<u32>(&vec, 2), Some(&3));
+        assert_eq!(bw_vector_get::<u32>(&vec, 3), None);
+        *bw_vector_get_mut::<u32>(&mut vec, 1).unwrap() = 5;
+        assert!(bw_vector_get_mut::<u32>(&mut vec, 3).is_none());
+    }
+    assert_eq!(values, [1, 5, 3]);
+
+    let empty = scr::BwVector {
+        data: std::ptr::null_mut(),
+        length: 0,
+        capacity: 0,
+    };
+    unsafe {
+        assert_eq!(bw_vector_get::<u32>(&empty, 0), None);
+    }
+}
+
+#[test]
+fn test_bw_vector_pop() {
+    let mut values = vec![1u32, 2];
+    let mut vec = scr::BwVector {
+        data: values.as_mut_ptr() as *mut libc::c_void,
+        length: values.len(),
+        capacity: values.capacity(),
+    };
+    unsafe {
+        assert_eq!(bw_vector_pop::<u32>(&mut vec), Some(2));
+        assert_eq!(vec.length, 1);
+        assert_eq!(bw_vector_pop::<u32>(&mut vec), Some(1));
+        assert_eq!(vec.length, 0);
+        assert_eq!(bw_vector_pop::<u32>(&mut vec), None);
+        assert_eq!(vec.length, 0);
+    }
+    assert_eq!(vec.capacity, values.capacity());
+}
+
+#[test]
+fn test_bw_vector_remove() {
+    let mut values = vec![1u32, 2, 3, 4];
+    let mut vec = scr::BwVector {
+        data: values.as_mut_ptr() as *mut libc::c_void,
+        length: values.len(),
+        capacity: values.capacity(),
+    };
+    unsafe {
+        assert_eq!(bw_vector_remove::<u32>(&mut vec, 1), Some(2));
+        assert_eq!(vec.length, 3);
+        assert_eq!(bw_vector_get::<u32>(&vec, 0), Some(&1));
+        assert_eq!(bw_vector_get::<u32>(&vec, 1), Some(&3));
+        assert_eq!(bw_vector_get::<u32>(&vec, 2), Some(&4));
+        assert_eq!(bw_vector_remove::<u32>(&mut vec, 2), Some(4));
+        assert_eq!(bw_vector_remove::<u32>(&mut vec, 0), Some(1));
+        assert_eq!(vec.length, 1);
+        assert_eq!(bw_vector_get::<u32>(&vec, 0), Some(&3));
+    }
+}
+
+#[test]
+fn test_bw_vector_insert() {
+    let mut values = Vec::with_capacity(8);
+    values.extend([1u32, 2, 3]);
+    let mut vec = scr::BwVector {
+        data: values.as_mut_ptr() as *mut libc::c_void,
+        length: values.len(),
+        capacity: values.capacity(),
+    };
+    unsafe {
+        bw_vector_insert::<u32>(&mut vec, 0, 10);
+        bw_vector_insert::<u32>(&mut vec, 2, 20);
+        bw_vector_insert::<u32>(&mut vec, 5, 30);
+        assert_eq!(vec.length, 6);
+        let result = (0..vec.length)
+            .map(|i| *bw_vector_get::<u32>(&vec, i).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(result, [10, 1, 20, 2, 3, 30]);
+
+        let data = vec.data;
+        bw_vector_clear(&mut vec);
+        assert_eq!(vec.length, 0);
+        assert_eq!(bw_vector_get::<u32>(&vec, 0), None);
+        bw_vector_insert::<u32>(&mut vec, 0, 40);
+        assert_eq!(vec.data, data);
+        assert_eq!(vec.capacity, values.capacity());
+        assert_eq!(bw_vector_get::<u32>(&vec, 0), Some(&40));
+    }
+}
+
+#[test]
+fn test_bw_vector_insert_grow_full() {
+    unsafe {
+        let data = test_malloc(4 * mem::size_of::<u32>()) as *mut u32;
+        for i in 0..4 {
+            data.add(i).write(i as u32 + 1);
+        }
+        let mut vec = scr::BwVector {
+            data: data as *mut libc::c_void,
+            length: 4,
+            capacity: 4,
+        };
+        bw_vector_insert_with::<u32>(&mut vec, 2, 10, test_malloc, test_free);
+        assert!(!vec.data.is_null());
+        assert_eq!(vec.length, 5);
+        assert_eq!(vec.capacity, 8);
+        let result = (0..vec.length)
+            .map(|i| *bw_vector_get::<u32>(&vec, i).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(result, [1, 2, 10, 3, 4]);
+        test_free(vec.data as *mut u8);
+    }
+}
+
+#[test]
+fn test_bw_vector_insert_grow_empty() {
+    let mut vec = scr::BwVector {
+        data: std::ptr::null_mut(),
+        length: 0,
+        capacity: 0,
+    };
+    unsafe {
+        for i in 0..10 {
+            bw_vector_insert_with::<u32>(&mut vec, vec.length, i, test_malloc, test_free);
+            assert!(!vec.data.is_null());
+            assert!(vec.capacity >= MIN_GROW_CAPACITY);
+            assert!(vec.capacity >= vec.length);
+        }
+        assert_eq!(vec.length, 10);
+        let result = (0..vec.length)
+            .map(|i| *bw_vector_get::<u32>(&vec, i).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(result, (0..10).collect::<Vec<_>>());
+        test_free(vec.data as *mut u8);
+    }
+}